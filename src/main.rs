@@ -1,12 +1,343 @@
 use reqwest::{Client, header};
 use anyhow::{Result, Context, anyhow};
+use futures::stream::{self, StreamExt};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::Path;
+use std::sync::Mutex;
+use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+/// The distinct failure modes this client can hit, as opposed to generic
+/// `anyhow` strings. Callers can match on these, e.g. `AuthenticationRequired`
+/// to trigger the token flow or `SpecViolation` to trigger push fallbacks.
+#[derive(Error, Debug)]
+enum OciError {
+    #[error("manifest not found: {0}")]
+    ManifestNotFound(String),
+
+    #[error("blob not found: {0}")]
+    BlobNotFound(String),
+
+    #[error("authentication required for {0}")]
+    AuthenticationRequired(String),
+
+    #[error("digest mismatch for {blob}: expected {expected}, got {actual}")]
+    DigestMismatch {
+        blob: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("registry error {code}: {message}")]
+    RegistryError { code: String, message: String },
+
+    #[error("response violates the OCI Distribution Spec: {0}")]
+    SpecViolation(String),
+}
+
+/// The OCI Distribution Spec requires manifest responses to carry a
+/// `Docker-Content-Digest` header; its absence is a spec violation rather
+/// than something we can recover from locally.
+fn check_content_digest(response: &reqwest::Response) -> Result<()> {
+    if response.headers().get("Docker-Content-Digest").is_none() {
+        return Err(OciError::SpecViolation(
+            "manifest response is missing the Docker-Content-Digest header".to_string(),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Identifies which kind of resource a failed response was fetching, so a
+/// `404` can be classified precisely instead of always reading as a missing
+/// manifest.
+#[derive(Clone, Copy)]
+enum ResourceKind {
+    Manifest,
+    Blob,
+}
+
+/// Classifies a failed response into an `OciError`, preferring the
+/// registry's standard `{"errors":[{"code","message"}]}` body when present.
+fn classify_error(kind: ResourceKind, status: reqwest::StatusCode, body: &str) -> OciError {
+    if let Ok(parsed) = serde_json::from_str::<Value>(body) {
+        if let Some(error) = parsed.get("errors").and_then(|e| e.as_array()).and_then(|a| a.first()) {
+            let code = error.get("code").and_then(|c| c.as_str()).unwrap_or("UNKNOWN").to_string();
+            let message = error.get("message").and_then(|m| m.as_str()).unwrap_or(body).to_string();
+            return OciError::RegistryError { code, message };
+        }
+    }
+
+    match (status, kind) {
+        (reqwest::StatusCode::NOT_FOUND, ResourceKind::Manifest) => OciError::ManifestNotFound(body.to_string()),
+        (reqwest::StatusCode::NOT_FOUND, ResourceKind::Blob) => OciError::BlobNotFound(body.to_string()),
+        (reqwest::StatusCode::UNAUTHORIZED, _) => OciError::AuthenticationRequired(body.to_string()),
+        _ => OciError::RegistryError {
+            code: status.to_string(),
+            message: body.to_string(),
+        },
+    }
+}
+
+/// Builds the shared `reqwest::Client`, applying TLS options from the
+/// environment: `REGISTRY_TLS_SKIP_VERIFY=1` accepts invalid certs (useful
+/// against local registries with self-signed certs) and `REGISTRY_CA_CERT`
+/// points at an extra CA bundle to trust.
+fn build_http_client() -> Result<Client> {
+    let mut builder = Client::builder();
+
+    if env::var("REGISTRY_TLS_SKIP_VERIFY").as_deref() == Ok("1") {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Ok(ca_path) = env::var("REGISTRY_CA_CERT") {
+        let pem = fs::read(&ca_path)
+            .with_context(|| format!("Failed to read CA certificate at {}", ca_path))?;
+        let cert = reqwest::Certificate::from_pem(&pem).context("Failed to parse CA certificate")?;
+        builder = builder.add_root_certificate(cert);
+    }
+
+    builder.build().context("Failed to build HTTP client")
+}
+
+/// Builds a `scheme://host<path>` registry URL. Defaults to `https://`;
+/// `REGISTRY_INSECURE=1` opts out to `http://` for local registries.
+fn registry_url(registry: &str, path: &str) -> String {
+    let scheme = if env::var("REGISTRY_INSECURE").as_deref() == Ok("1") { "http" } else { "https" };
+    format!("{}://{}{}", scheme, registry, path)
+}
+
+/// Wraps a `reqwest::Client` with a cached bearer token so registries that
+/// require auth (ghcr.io, Docker Hub, quay.io, ...) work transparently.
+struct RegistryClient {
+    http: Client,
+    token: Mutex<Option<String>>,
+}
+
+impl RegistryClient {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            http: build_http_client()?,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Performs a `GET` request, following the OCI Distribution Spec's bearer
+    /// token challenge: on a `401` with a `WWW-Authenticate: Bearer ...`
+    /// header, fetch a token from the advertised realm and retry once.
+    async fn call(&self, url: &str, accept: &str) -> Result<reqwest::Response> {
+        let response = self.get_with_auth(url, accept).await?;
+
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        let Some(challenge) = challenge else {
+            return Ok(response);
+        };
+
+        if let Some(token) = self.authenticate(&challenge).await? {
+            *self.token.lock().unwrap() = Some(token);
+            return self.get_with_auth(url, accept).await;
+        }
+
+        Ok(response)
+    }
+
+    async fn get_with_auth(&self, url: &str, accept: &str) -> Result<reqwest::Response> {
+        let mut request = self.http.get(url).header(header::ACCEPT, accept);
+
+        if let Some(token) = self.token.lock().unwrap().clone() {
+            request = request.bearer_auth(token);
+        }
+
+        request
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))
+    }
+
+    /// Parses a `Bearer realm="...",service="...",scope="..."` challenge and
+    /// requests a token from the realm, attaching Basic credentials when
+    /// configured and falling back to an anonymous request otherwise.
+    async fn authenticate(&self, challenge: &str) -> Result<Option<String>> {
+        let Some(params) = challenge.strip_prefix("Bearer ") else {
+            return Ok(None);
+        };
+
+        let params = parse_challenge_params(params);
+        let realm = params
+            .get("realm")
+            .ok_or_else(|| anyhow!("Bearer challenge is missing a realm: {}", challenge))?;
+
+        let mut request = self.http.get(realm);
+        if let Some(service) = params.get("service") {
+            request = request.query(&[("service", service)]);
+        }
+        if let Some(scope) = params.get("scope") {
+            request = request.query(&[("scope", scope)]);
+        }
+
+        if let Some((user, pass)) = registry_credentials() {
+            request = request.basic_auth(user, Some(pass));
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach auth realm {}", realm))?;
+
+        if !response.status().is_success() {
+            println!("  Auth realm returned {}, continuing anonymously", response.status());
+            return Ok(None);
+        }
+
+        let body: Value = response.json().await.context("Failed to parse token response")?;
+        let token = body
+            .get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(|t| t.as_str())
+            .map(|s| s.to_string());
+
+        Ok(token)
+    }
+}
+
+/// Splits a challenge's `key="value", key2="value2"` parameter list into a map.
+fn parse_challenge_params(params: &str) -> HashMap<String, String> {
+    params
+        .split(',')
+        .filter_map(|part| {
+            let (key, value) = part.trim().split_once('=')?;
+            Some((key.trim().to_string(), value.trim().trim_matches('"').to_string()))
+        })
+        .collect()
+}
+
+/// Looks up registry credentials from `REGISTRY_USERNAME`/`REGISTRY_PASSWORD`,
+/// falling back to the first entry in `~/.docker/config.json`.
+fn registry_credentials() -> Option<(String, String)> {
+    if let (Ok(user), Ok(pass)) = (env::var("REGISTRY_USERNAME"), env::var("REGISTRY_PASSWORD")) {
+        return Some((user, pass));
+    }
+
+    docker_config_credentials()
+}
+
+fn docker_config_credentials() -> Option<(String, String)> {
+    let home = env::var("HOME").ok()?;
+    let contents = fs::read_to_string(Path::new(&home).join(".docker/config.json")).ok()?;
+    let config: Value = serde_json::from_str(&contents).ok()?;
+
+    let auth = config
+        .get("auths")?
+        .as_object()?
+        .values()
+        .next()?
+        .get("auth")?
+        .as_str()?;
+
+    let decoded = String::from_utf8(base64_decode(auth)?).ok()?;
+    let (user, pass) = decoded.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+/// Minimal standard-alphabet base64 decoder, just enough to read the `auth`
+/// field Docker stores in `~/.docker/config.json` (`user:pass` encoded).
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let input = input.trim_end_matches('=');
+
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut out = Vec::new();
+
+    for c in input.bytes() {
+        let value = ALPHABET.iter().position(|&b| b == c)? as u32;
+        bits = (bits << 6) | value;
+        bit_count += 6;
+
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Single-manifest media types this client knows how to download layers for.
+const SUPPORTED_MANIFEST_TYPES: &[&str] = &[
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.wasm.config.v0+json",
+];
+
+/// Returns true if `media_type` identifies an OCI image index or the
+/// equivalent Docker manifest list, rather than a single manifest.
+fn is_image_index(media_type: &str) -> bool {
+    media_type == "application/vnd.oci.image.index.v1+json"
+        || media_type == "application/vnd.docker.distribution.manifest.list.v2+json"
+}
+
+/// Picks the manifest digest in an image index matching `TARGET_OS`/
+/// `TARGET_ARCH` (and optional `TARGET_VARIANT`), defaulting to the host
+/// platform via `std::env::consts`.
+fn select_platform_manifest(index: &Value) -> Result<String> {
+    // Rust's host names differ from the GOOS/GOARCH-style names OCI indexes use
+    let host_os = match std::env::consts::OS { "macos" => "darwin", other => other };
+    let host_arch = match std::env::consts::ARCH { "x86_64" => "amd64", "aarch64" => "arm64", "x86" => "386", other => other };
+    let target_os = env::var("TARGET_OS").unwrap_or_else(|_| host_os.to_string());
+    let target_arch = env::var("TARGET_ARCH").unwrap_or_else(|_| host_arch.to_string());
+    let target_variant = env::var("TARGET_VARIANT").ok();
+
+    let manifests = index
+        .get("manifests")
+        .and_then(|m| m.as_array())
+        .ok_or_else(|| anyhow!("Image index has no \"manifests\" array"))?;
+
+    for entry in manifests {
+        let Some(platform) = entry.get("platform") else { continue };
+
+        let os = platform.get("os").and_then(|o| o.as_str()).unwrap_or("");
+        let arch = platform.get("architecture").and_then(|a| a.as_str()).unwrap_or("");
+        if os != target_os || arch != target_arch {
+            continue;
+        }
+
+        if let Some(variant) = &target_variant {
+            let entry_variant = platform.get("variant").and_then(|v| v.as_str());
+            if entry_variant != Some(variant.as_str()) {
+                continue;
+            }
+        }
+
+        if let Some(digest) = entry.get("digest").and_then(|d| d.as_str()) {
+            return Ok(digest.to_string());
+        }
+    }
+
+    Err(anyhow!(
+        "No manifest in the image index matches platform {}/{}",
+        target_os, target_arch
+    ))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Get registry URL, namespace and package name from environment variables
@@ -22,24 +353,18 @@ async fn main() -> Result<()> {
     // In OCI Distribution Spec, the correct URL format is:
     // /v2/<name>/manifests/<reference>
     // Where <name> is the repository name (namespace/package)
-    let url = format!("http://{}/v2/{}/{}/manifests/{}",
-        registry,
-        namespace,
-        name,
-        version);
+    let url = registry_url(&registry, &format!("/v2/{}/{}/manifests/{}", namespace, name, version));
 
     println!("Fetching manifest from: {}", url);
 
-    // Create a client with appropriate headers for OCI registry
-    let client = Client::new();
+    // Client wraps a shared reqwest::Client and the bearer token cache
+    let client = RegistryClient::new()?;
 
     // Create request with proper Accept headers for OCI manifest
     // Include multiple acceptable formats including the WASM config type
-    let response = client.get(&url)
-        .header(header::ACCEPT, "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json, application/vnd.wasm.config.v0+json")
-        .send()
-        .await
-        .context("Failed to send request")?;
+    let response = client
+        .call(&url, "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json, application/vnd.wasm.config.v0+json")
+        .await?;
 
     // Debug information
     println!("Response status: {}", response.status());
@@ -57,8 +382,8 @@ async fn main() -> Result<()> {
 
         // Try the catalog endpoint to see what's available
         println!("\nAttempting to list available repositories...");
-        let catalog_url = format!("http://{}/v2/_catalog", registry);
-        match client.get(&catalog_url).send().await {
+        let catalog_url = registry_url(&registry, "/v2/_catalog");
+        match client.call(&catalog_url, "application/json").await {
             Ok(catalog_resp) => {
                 if catalog_resp.status().is_success() {
                     let catalog: Value = catalog_resp.json().await?;
@@ -70,61 +395,112 @@ async fn main() -> Result<()> {
             Err(e) => println!("Error listing repositories: {}", e),
         }
 
-        return Err(anyhow!("HTTP error {}: {}", status, text));
+        return Err(classify_error(ResourceKind::Manifest, status, &text).into());
     }
 
+    check_content_digest(&response)?;
+
     // Parse the JSON response
     let manifest: Value = response.json().await.context("Failed to parse manifest JSON")?;
 
-    // Save the manifest
+    // Save the manifest (or image index) the registry returned, re-serialized
+    // as pretty-printed JSON
     let manifest_path = format!("{}/manifest.json", output_dir);
     fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
         .context("Failed to save manifest")?;
 
     println!("Saved manifest to {}", manifest_path);
 
+    // Multi-platform images publish an index/manifest-list instead of a
+    // single manifest; resolve it to the manifest for our target platform
+    let media_type = manifest.get("mediaType").and_then(|m| m.as_str()).unwrap_or("").to_string();
+    let manifest = if is_image_index(&media_type) {
+        println!("\nManifest is an image index; resolving platform-specific manifest...");
+        let child_digest = select_platform_manifest(&manifest)?;
+
+        let child_url = registry_url(&registry, &format!("/v2/{}/{}/manifests/{}", namespace, name, child_digest));
+        let child_response = client
+            .call(&child_url, "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json")
+            .await?;
+
+        if !child_response.status().is_success() {
+            let status = child_response.status();
+            let text = child_response.text().await.unwrap_or_default();
+            return Err(classify_error(ResourceKind::Manifest, status, &text).into());
+        }
+
+        check_content_digest(&child_response)?;
+        child_response.json().await.context("Failed to parse platform manifest JSON")?
+    } else {
+        manifest
+    };
+
+    let resolved_media_type = manifest.get("mediaType").and_then(|m| m.as_str()).unwrap_or("");
+    if !resolved_media_type.is_empty() && !SUPPORTED_MANIFEST_TYPES.contains(&resolved_media_type) {
+        return Err(OciError::UnsupportedMediaType(resolved_media_type.to_string()).into());
+    }
+
     // For WASM artifacts, we care most about the layers which contain the WASM modules
     if let Some(layers) = manifest.get("layers").and_then(|l| l.as_array()) {
         println!("\nFound {} layer(s) in the manifest", layers.len());
 
-        for (i, layer) in layers.iter().enumerate() {
-            if let Some(digest) = layer.get("digest").and_then(|d| d.as_str()) {
-                // Check if this is a WASM file based on mediaType
-                let is_wasm = layer.get("mediaType")
-                    .and_then(|m| m.as_str())
-                    .map(|m| m == "application/wasm")
-                    .unwrap_or(false);
-
-                // Get the filename from annotations if available
-                let filename = layer.get("annotations")
-                    .and_then(|a| a.get("org.opencontainers.image.title"))
-                    .and_then(|t| t.as_str())
-                    .map(|s| Path::new(s).file_name().and_then(|f| f.to_str()).unwrap_or(s))
-                    .unwrap_or_else(|| {
-                        let s = if is_wasm {
-                            format!("module_{}.wasm", i)
-                        } else {
-                            format!("blob_{}", i)
-                        };
-                        Box::leak(s.into_boxed_str())
-                    });
+        // buffer_unordered(0) never polls anything and hangs forever, so
+        // clamp rather than trust the env var verbatim
+        let max_concurrent = env::var("MAX_CONCURRENT_DOWNLOADS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(4)
+            .max(1);
+
+        // Pre-borrow as `Copy` references so the `move` blocks below can be
+        // created once per layer without moving `client`/`registry`/etc. out
+        // of this scope on the first iteration
+        let client_ref = &client;
+        let registry_ref = registry.as_str();
+        let namespace_ref = namespace.as_str();
+        let name_ref = name.as_str();
+        let output_dir_ref = output_dir.as_str();
+
+        let downloads = layers.iter().enumerate().filter_map(move |(i, layer)| {
+            let digest = layer.get("digest").and_then(|d| d.as_str())?;
+
+            // Check if this is a WASM file based on mediaType
+            let is_wasm = layer.get("mediaType")
+                .and_then(|m| m.as_str())
+                .map(|m| m == "application/wasm")
+                .unwrap_or(false);
 
+            // Get the filename from annotations if available
+            let filename = layer.get("annotations")
+                .and_then(|a| a.get("org.opencontainers.image.title"))
+                .and_then(|t| t.as_str())
+                .map(|s| Path::new(s).file_name().and_then(|f| f.to_str()).unwrap_or(s))
+                .unwrap_or_else(|| {
+                    let s = if is_wasm {
+                        format!("module_{}.wasm", i)
+                    } else {
+                        format!("blob_{}", i)
+                    };
+                    Box::leak(s.into_boxed_str())
+                });
+
+            Some(async move {
                 println!("Downloading layer {}: {} ({})",
                          i,
                          filename,
                          if is_wasm { "WASM module" } else { "other content" });
 
-                // Download the blob
-                download_blob(
-                    &client,
-                    &registry,
-                    &namespace,
-                    &name,
-                    digest,
-                    &filename,
-                    &output_dir
-                ).await?;
-            }
+                download_blob(client_ref, registry_ref, namespace_ref, name_ref, digest, filename, output_dir_ref).await
+            })
+        });
+
+        let results: Vec<Result<()>> = stream::iter(downloads)
+            .buffer_unordered(max_concurrent)
+            .collect()
+            .await;
+
+        for result in results {
+            result?;
         }
     } else {
         println!("No layers found in the manifest. Checking for other content references...");
@@ -148,7 +524,7 @@ async fn main() -> Result<()> {
 }
 
 async fn download_blob(
-    client: &Client,
+    client: &RegistryClient,
     registry: &str,
     namespace: &str,
     name: &str,
@@ -156,33 +532,60 @@ async fn download_blob(
     output_filename: &str,
     output_dir: &str
 ) -> Result<()> {
-    let blob_url = format!("http://{}/v2/{}/{}/blobs/{}",
-        registry, namespace, name, digest);
+    let blob_url = registry_url(registry, &format!("/v2/{}/{}/blobs/{}", namespace, name, digest));
 
     println!("  Fetching from: {}", blob_url);
 
-    let resp = client.get(&blob_url)
-        .send()
+    let resp = client
+        .call(&blob_url, "*/*")
         .await
         .with_context(|| format!("Failed to download blob: {}", digest))?;
 
     if !resp.status().is_success() {
-        return Err(anyhow!("Failed to download blob {}: {}", digest, resp.status()));
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        return Err(classify_error(ResourceKind::Blob, status, &text).into());
     }
 
     let output_path = format!("{}/{}", output_dir, output_filename);
-    let bytes = resp.bytes().await?;
+    let partial_path = format!("{}.partial", output_path);
 
-    // Save the blob content
-    let mut file = File::create(&output_path).await?;
-    file.write_all(&bytes).await?;
+    // Stream the body to a partial file, hashing each chunk as it arrives so
+    // we never have to buffer the whole blob in memory; only rename it into
+    // place once the digest is verified, so a truncated/corrupted download
+    // never ends up at the final path
+    let mut file = File::create(&partial_path).await?;
+    let mut hasher = Sha256::new();
+    let mut total_bytes = 0usize;
+    let mut chunks = resp.bytes_stream();
 
-    println!("  Saved to {} ({} bytes)", output_path, bytes.len());
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.with_context(|| format!("Failed to read blob stream for {}", digest))?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        total_bytes += chunk.len();
+    }
+    drop(file);
 
-    // If it seems to be JSON, also save a pretty version
-    if output_filename.ends_with(".json") || bytes.len() > 0 && bytes[0] == b'{' {
-        if let Ok(json_str) = String::from_utf8(bytes.to_vec()) {
-            if let Ok(json_value) = serde_json::from_str::<Value>(&json_str) {
+    let computed_digest = format!("sha256:{:x}", hasher.finalize());
+    if computed_digest != digest {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        return Err(OciError::DigestMismatch {
+            blob: output_filename.to_string(),
+            expected: digest.to_string(),
+            actual: computed_digest,
+        }
+        .into());
+    }
+
+    tokio::fs::rename(&partial_path, &output_path).await?;
+
+    println!("  Saved to {} ({} bytes, digest verified)", output_path, total_bytes);
+
+    // If it's JSON, also save a pretty version
+    if output_filename.ends_with(".json") {
+        if let Ok(contents) = fs::read_to_string(&output_path) {
+            if let Ok(json_value) = serde_json::from_str::<Value>(&contents) {
                 let pretty_path = format!("{}/{}_pretty.json", output_dir,
                     output_filename.trim_end_matches(".json"));
                 fs::write(&pretty_path, serde_json::to_string_pretty(&json_value)?)?;
@@ -193,3 +596,165 @@ async fn download_blob(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TARGET_OS/TARGET_ARCH/TARGET_VARIANT are process-wide env vars, so
+    // serialize the tests that set them to avoid cross-test races.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn parse_challenge_params_reads_quoted_keys() {
+        let params = parse_challenge_params(
+            r#"realm="https://auth.example.com/token",service="registry.example.com",scope="repository:foo:pull""#,
+        );
+
+        assert_eq!(params.get("realm").map(String::as_str), Some("https://auth.example.com/token"));
+        assert_eq!(params.get("service").map(String::as_str), Some("registry.example.com"));
+        assert_eq!(params.get("scope").map(String::as_str), Some("repository:foo:pull"));
+    }
+
+    #[test]
+    fn parse_challenge_params_ignores_malformed_segments() {
+        let params = parse_challenge_params(r#"realm="https://auth.example.com/token", garbage, service="x""#);
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params.get("realm").map(String::as_str), Some("https://auth.example.com/token"));
+        assert_eq!(params.get("service").map(String::as_str), Some("x"));
+    }
+
+    #[test]
+    fn base64_decode_roundtrips_docker_auth_field() {
+        // "user:pass" base64-encoded, the shape ~/.docker/config.json stores
+        let decoded = base64_decode("dXNlcjpwYXNz").expect("valid base64");
+        assert_eq!(String::from_utf8(decoded).unwrap(), "user:pass");
+    }
+
+    #[test]
+    fn base64_decode_handles_padding() {
+        // "a" base64-encoded with padding
+        let decoded = base64_decode("YQ==").expect("valid base64");
+        assert_eq!(decoded, b"a");
+    }
+
+    #[test]
+    fn base64_decode_rejects_invalid_characters() {
+        assert!(base64_decode("not valid base64!!").is_none());
+    }
+
+    #[test]
+    fn is_image_index_recognizes_oci_and_docker_types() {
+        assert!(is_image_index("application/vnd.oci.image.index.v1+json"));
+        assert!(is_image_index("application/vnd.docker.distribution.manifest.list.v2+json"));
+        assert!(!is_image_index("application/vnd.oci.image.manifest.v1+json"));
+        assert!(!is_image_index(""));
+    }
+
+    #[test]
+    fn select_platform_manifest_matches_os_and_arch() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        env::set_var("TARGET_OS", "linux");
+        env::set_var("TARGET_ARCH", "arm64");
+        env::remove_var("TARGET_VARIANT");
+
+        let index: Value = serde_json::from_str(
+            r#"{
+                "manifests": [
+                    {"digest": "sha256:amd64", "platform": {"os": "linux", "architecture": "amd64"}},
+                    {"digest": "sha256:arm64", "platform": {"os": "linux", "architecture": "arm64"}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(select_platform_manifest(&index).unwrap(), "sha256:arm64");
+
+        env::remove_var("TARGET_OS");
+        env::remove_var("TARGET_ARCH");
+    }
+
+    #[test]
+    fn select_platform_manifest_respects_variant() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        env::set_var("TARGET_OS", "linux");
+        env::set_var("TARGET_ARCH", "arm");
+        env::set_var("TARGET_VARIANT", "v7");
+
+        let index: Value = serde_json::from_str(
+            r#"{
+                "manifests": [
+                    {"digest": "sha256:v6", "platform": {"os": "linux", "architecture": "arm", "variant": "v6"}},
+                    {"digest": "sha256:v7", "platform": {"os": "linux", "architecture": "arm", "variant": "v7"}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(select_platform_manifest(&index).unwrap(), "sha256:v7");
+
+        env::remove_var("TARGET_OS");
+        env::remove_var("TARGET_ARCH");
+        env::remove_var("TARGET_VARIANT");
+    }
+
+    #[test]
+    fn select_platform_manifest_errors_when_nothing_matches() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        env::set_var("TARGET_OS", "plan9");
+        env::set_var("TARGET_ARCH", "mips");
+        env::remove_var("TARGET_VARIANT");
+
+        let index: Value = serde_json::from_str(
+            r#"{"manifests": [{"digest": "sha256:amd64", "platform": {"os": "linux", "architecture": "amd64"}}]}"#,
+        )
+        .unwrap();
+
+        assert!(select_platform_manifest(&index).is_err());
+
+        env::remove_var("TARGET_OS");
+        env::remove_var("TARGET_ARCH");
+    }
+
+    #[test]
+    fn classify_error_prefers_registry_error_body() {
+        let body = r#"{"errors":[{"code":"NAME_UNKNOWN","message":"repository name not known to registry"}]}"#;
+        let error = classify_error(ResourceKind::Manifest, reqwest::StatusCode::NOT_FOUND, body);
+
+        match error {
+            OciError::RegistryError { code, message } => {
+                assert_eq!(code, "NAME_UNKNOWN");
+                assert_eq!(message, "repository name not known to registry");
+            }
+            other => panic!("expected RegistryError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn classify_error_distinguishes_manifest_and_blob_not_found() {
+        let manifest_error = classify_error(ResourceKind::Manifest, reqwest::StatusCode::NOT_FOUND, "not json");
+        assert!(matches!(manifest_error, OciError::ManifestNotFound(_)));
+
+        let blob_error = classify_error(ResourceKind::Blob, reqwest::StatusCode::NOT_FOUND, "not json");
+        assert!(matches!(blob_error, OciError::BlobNotFound(_)));
+    }
+
+    #[test]
+    fn classify_error_maps_401_regardless_of_resource_kind() {
+        let error = classify_error(ResourceKind::Blob, reqwest::StatusCode::UNAUTHORIZED, "not json");
+        assert!(matches!(error, OciError::AuthenticationRequired(_)));
+    }
+
+    #[test]
+    fn classify_error_falls_back_to_status_code() {
+        let error = classify_error(ResourceKind::Manifest, reqwest::StatusCode::INTERNAL_SERVER_ERROR, "boom");
+        match error {
+            OciError::RegistryError { code, message } => {
+                assert_eq!(code, "500 Internal Server Error");
+                assert_eq!(message, "boom");
+            }
+            other => panic!("expected RegistryError, got {:?}", other),
+        }
+    }
+}